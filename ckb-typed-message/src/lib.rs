@@ -1,7 +1,11 @@
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 extern crate alloc;
 pub mod blake2b;
+#[cfg(feature = "std")]
+pub mod builder;
+pub mod multisig;
 pub mod schemas;
+pub mod varsize;
 
 use alloc::vec::Vec;
 use blake2b::new_blake2b;
@@ -9,7 +13,10 @@ use ckb_std::{
     ckb_constants::Source,
     ckb_types::packed::CellInput,
     error::SysError,
-    high_level::{load_tx_hash, load_witness, QueryIter},
+    high_level::{
+        load_cell, load_cell_data, load_cell_dep, load_cell_lock_hash, load_header, load_input,
+        load_script_hash, load_tx_hash, load_witness, QueryIter,
+    },
     syscalls::load_transaction,
 };
 use core::convert::Into;
@@ -19,18 +26,37 @@ use molecule::{
     NUMBER_SIZE,
 };
 use schemas::{
-    basic::SighashWithAction,
+    basic::{Otx, OtxStart, SighashWithAction},
     top_level::{
         ExtendedWitness, ExtendedWitnessReader, ExtendedWitnessUnion, ExtendedWitnessUnionReader,
     },
 };
+use varsize::{check_prealloc, write_varsize};
 
 #[derive(Eq, PartialEq, Debug, Clone, Copy)]
 pub enum Error {
     Sys(SysError),
-    MoleculeEncoding,
-    WrongSighashWithAction,
-    WrongWitnessLayout,
+    MoleculeEncoding(VerificationError),
+    /// `fetch_sighash`: the witness at `index` is not a Sighash/SighashWithAction.
+    UnexpectedWitnessVariant { index: usize },
+    /// `fetch_sighash_with_action`: a second SighashWithAction was found at
+    /// `second`, the first one already being at `first`.
+    MultipleSighashWithAction { first: usize, second: usize },
+    /// `fetch_sighash_with_action`: no SighashWithAction witness was found.
+    MissingSighashWithAction,
+    /// `check_others_in_group`: the witness at `index` should be empty.
+    NonEmptyWitnessInGroup { index: usize },
+    /// `resolve_otx`/`generate_otx_hash`: OtxStart/Otx witnesses are missing,
+    /// duplicated, overlapping, or out of range.
+    WrongOtxLayout,
+    /// `calculate_inputs_len`: the transaction's offset table doesn't add up.
+    MalformedTransaction,
+    /// `multisig::verify_multisig`: the `lock` field is not a well-formed,
+    /// sufficiently-signed multisig payload.
+    InvalidMultisig,
+    /// `varsize::check_prealloc`: a witness/lock/message length beyond
+    /// `varsize::MAX_WITNESS_PREALLOC`.
+    NonCanonicalLength,
 }
 
 impl From<SysError> for Error {
@@ -40,8 +66,26 @@ impl From<SysError> for Error {
 }
 
 impl From<VerificationError> for Error {
-    fn from(_: VerificationError) -> Self {
-        Error::MoleculeEncoding
+    fn from(e: VerificationError) -> Self {
+        Error::MoleculeEncoding(e)
+    }
+}
+
+impl Error {
+    /// A stable, compact exit code so a lock script can `return` it directly.
+    pub fn code(&self) -> i8 {
+        match self {
+            Error::Sys(_) => 1,
+            Error::MoleculeEncoding(_) => 2,
+            Error::UnexpectedWitnessVariant { .. } => 3,
+            Error::MultipleSighashWithAction { .. } => 4,
+            Error::MissingSighashWithAction => 5,
+            Error::NonEmptyWitnessInGroup { .. } => 6,
+            Error::WrongOtxLayout => 7,
+            Error::MalformedTransaction => 8,
+            Error::InvalidMultisig => 9,
+            Error::NonCanonicalLength => 10,
+        }
     }
 }
 
@@ -50,19 +94,14 @@ impl From<VerificationError> for Error {
 /// Used by lock script
 ///
 pub fn fetch_sighash() -> Result<ExtendedWitness, Error> {
-    match load_witness(0, Source::GroupInput) {
-        Ok(witness) => {
-            if let Ok(r) = ExtendedWitnessReader::from_slice(&witness) {
-                match r.to_enum() {
-                    ExtendedWitnessUnionReader::SighashWithAction(_)
-                    | ExtendedWitnessUnionReader::Sighash(_) => Ok(r.to_entity()),
-                    _ => Err(Error::MoleculeEncoding),
-                }
-            } else {
-                Err(Error::MoleculeEncoding)
-            }
+    let witness = load_witness(0, Source::GroupInput)?;
+    check_prealloc(witness.len() as u64)?;
+    let r = ExtendedWitnessReader::from_slice(&witness)?;
+    match r.to_enum() {
+        ExtendedWitnessUnionReader::SighashWithAction(_) | ExtendedWitnessUnionReader::Sighash(_) => {
+            Ok(r.to_entity())
         }
-        Err(e) => Err(e.into()),
+        _ => Err(Error::UnexpectedWitnessVariant { index: 0 }),
     }
 }
 
@@ -71,24 +110,25 @@ pub fn fetch_sighash() -> Result<ExtendedWitness, Error> {
 /// This function can also check the count of SighashWithAction is one.
 ///
 pub fn fetch_sighash_with_action() -> Result<SighashWithAction, Error> {
-    let mut result = None;
+    let mut result: Option<(usize, SighashWithAction)> = None;
 
-    for witness in QueryIter::new(load_witness, Source::Input) {
+    for (index, witness) in QueryIter::new(load_witness, Source::Input).enumerate() {
+        check_prealloc(witness.len() as u64)?;
         if let Ok(r) = ExtendedWitnessReader::from_slice(&witness) {
             if let ExtendedWitnessUnionReader::SighashWithAction(s) = r.to_enum() {
-                if result.is_some() {
-                    return Err(Error::WrongSighashWithAction);
-                } else {
-                    result = Some(s.to_entity());
+                if let Some((first, _)) = result {
+                    return Err(Error::MultipleSighashWithAction {
+                        first,
+                        second: index,
+                    });
                 }
+                result = Some((index, s.to_entity()));
             }
         }
     }
-    if result.is_some() {
-        return Ok(result.unwrap());
-    } else {
-        return Err(Error::WrongSighashWithAction);
-    }
+    result
+        .map(|(_, sighash_with_action)| sighash_with_action)
+        .ok_or(Error::MissingSighashWithAction)
 }
 
 ///
@@ -96,9 +136,13 @@ pub fn fetch_sighash_with_action() -> Result<SighashWithAction, Error> {
 /// first one should be empty
 ///
 pub fn check_others_in_group() -> Result<(), Error> {
-    for witness in QueryIter::new(load_witness, Source::GroupInput).skip(1) {
+    for (index, witness) in QueryIter::new(load_witness, Source::GroupInput)
+        .enumerate()
+        .skip(1)
+    {
+        check_prealloc(witness.as_slice().len() as u64)?;
         if witness.as_slice().len() != 0 {
-            return Err(Error::WrongWitnessLayout);
+            return Err(Error::NonEmptyWitnessInGroup { index });
         }
     }
     Ok(())
@@ -109,7 +153,53 @@ pub fn check_others_in_group() -> Result<(), Error> {
 // 1. Variable length data should hash the length.
 // 2. Fixed length data don't need to hash the length.
 //
+/// The pure hashing core behind `generate_skeleton_hash`: hash `tx_hash`
+/// followed by each witness in `witnesses`, length-prefixed. Kept separate
+/// from the syscall loop so host-side code (`builder::CobuildTxBuilder`)
+/// can replay the exact same rule over a `TransactionView` it holds
+/// in-memory, without a syscall mock.
+pub(crate) fn skeleton_hash_over<'a>(
+    tx_hash: &[u8],
+    witnesses: impl Iterator<Item = &'a [u8]>,
+) -> [u8; 32] {
+    let mut hasher = new_blake2b();
+    hasher.update(tx_hash);
+    for witness in witnesses {
+        hasher.update(&(witness.len() as u64).to_le_bytes());
+        hasher.update(witness);
+    }
+    let mut output = [0u8; 32];
+    hasher.finalize(&mut output);
+    output
+}
+
 pub fn generate_skeleton_hash() -> Result<[u8; 32], Error> {
+    let tx_hash = load_tx_hash()?;
+
+    let mut witnesses = Vec::new();
+    let mut i = calculate_inputs_len()?;
+    loop {
+        match load_witness(i, Source::Input) {
+            Ok(w) => witnesses.push(w),
+            Err(SysError::IndexOutOfBound) => break,
+            Err(e) => return Err(e.into()),
+        }
+        i += 1;
+    }
+
+    Ok(skeleton_hash_over(
+        &tx_hash,
+        witnesses.iter().map(Vec::as_slice),
+    ))
+}
+
+///
+/// Same rule as `generate_skeleton_hash`, but witness lengths are written
+/// with `write_varsize`'s canonical variable-length prefix instead of a
+/// fixed 8-byte LE length, to save hashing bytes on the common case of
+/// small witnesses.
+///
+pub fn generate_skeleton_hash_v2() -> Result<[u8; 32], Error> {
     let mut hasher = new_blake2b();
     hasher.update(&load_tx_hash()?);
 
@@ -117,7 +207,7 @@ pub fn generate_skeleton_hash() -> Result<[u8; 32], Error> {
     loop {
         match load_witness(i, Source::Input) {
             Ok(w) => {
-                hasher.update(&(w.len() as u64).to_le_bytes());
+                write_varsize(&mut hasher, w.len() as u64);
                 hasher.update(&w);
             }
             Err(SysError::IndexOutOfBound) => {
@@ -150,17 +240,24 @@ pub fn generate_final_hash(skeleton_hash: &[u8; 32], typed_message: &[u8]) -> [u
 /// full-size and offset are 4 bytes, so we can read the inputs-offset and outputs-offset at [28, 36),
 /// then we can get the length of inputs by calculating the difference between inputs-offset and outputs-offset
 ///
-fn calculate_inputs_len() -> Result<usize, SysError> {
+fn calculate_inputs_len() -> Result<usize, Error> {
     let mut offsets = [0u8; 8];
     match load_transaction(&mut offsets, 28) {
         // this syscall will always return SysError::LengthNotEnough since we only load 8 bytes, let's ignore it
         Err(SysError::LengthNotEnough(_)) => {}
-        Err(SysError::Unknown(e)) => return Err(SysError::Unknown(e)),
+        Err(SysError::Unknown(e)) => return Err(SysError::Unknown(e).into()),
         _ => unreachable!(),
     }
     let inputs_offset = u32::from_le_bytes(offsets[0..4].try_into().unwrap());
     let outputs_offset = u32::from_le_bytes(offsets[4..8].try_into().unwrap());
-    Ok((outputs_offset as usize - inputs_offset as usize - NUMBER_SIZE) / CellInput::TOTAL_SIZE)
+    let raw_len = (outputs_offset as usize)
+        .checked_sub(inputs_offset as usize)
+        .and_then(|diff| diff.checked_sub(NUMBER_SIZE))
+        .ok_or(Error::MalformedTransaction)?;
+    if raw_len % CellInput::TOTAL_SIZE != 0 {
+        return Err(Error::MalformedTransaction);
+    }
+    Ok(raw_len / CellInput::TOTAL_SIZE)
 }
 
 ///
@@ -178,11 +275,222 @@ pub fn parse_typed_message() -> Result<([u8; 32], Vec<u8>), Error> {
     let (lock, typed_message) = match witness.to_enum() {
         ExtendedWitnessUnion::SighashWithAction(s) => (s.lock(), s.message()),
         ExtendedWitnessUnion::Sighash(s) => (s.lock(), sighash_with_action.message()),
-        _ => {
-            return Err(Error::WrongSighashWithAction);
-        }
+        _ => return Err(Error::UnexpectedWitnessVariant { index: 0 }),
     };
     let skeleton_hash = generate_skeleton_hash()?;
     let digest_message = generate_final_hash(&skeleton_hash, typed_message.as_slice());
-    Ok((digest_message, lock.raw_data().into()))
+    let lock = lock.raw_data();
+    check_prealloc(lock.len() as u64)?;
+    Ok((digest_message, lock.into()))
+}
+
+fn unpack_u32<T: Entity>(field: T) -> u32 {
+    let data = field.raw_data();
+    u32::from_le_bytes(data.as_ref().try_into().unwrap())
+}
+
+///
+/// An `Otx` witness together with the absolute starting offsets it covers,
+/// resolved by combining the transaction-wide `OtxStart` marker with the
+/// counts of every `Otx` that precedes it in witness order.
+///
+struct ResolvedOtx {
+    start_input_cell: u32,
+    start_output_cell: u32,
+    start_cell_deps: u32,
+    start_header_deps: u32,
+    input_cells: u32,
+    output_cells: u32,
+    cell_deps: u32,
+    header_deps: u32,
+    message: Vec<u8>,
+    lock: Vec<u8>,
+}
+
+///
+/// Locate the current script group's own `Otx` witness and resolve its
+/// starting offsets against the single `OtxStart` marker and any `Otx`
+/// witnesses preceding it. Returns `Error::WrongOtxLayout` if `OtxStart` is
+/// missing, duplicated, or the group's witness is not an `Otx`.
+///
+fn resolve_otx() -> Result<ResolvedOtx, Error> {
+    let group_witness = load_witness(0, Source::GroupInput)?;
+    check_prealloc(group_witness.len() as u64)?;
+    let otx = match ExtendedWitnessReader::from_slice(&group_witness)?.to_enum() {
+        ExtendedWitnessUnionReader::Otx(o) => o.to_entity(),
+        _ => return Err(Error::WrongOtxLayout),
+    };
+
+    let own_script_hash = load_script_hash()?;
+    let own_index = QueryIter::new(load_cell_lock_hash, Source::Input)
+        .position(|hash| hash == own_script_hash)
+        .ok_or(Error::WrongOtxLayout)?;
+
+    // Scan every witness of the whole transaction, not just the ones before
+    // our own, so a second OtxStart placed after the last Otx group is
+    // rejected too. Only witnesses strictly before `own_index` contribute
+    // to the cursor, and the sole OtxStart must itself be one of them: one
+    // placed at or after our own witness doesn't actually precede us, so it
+    // can't be what resolved our offsets.
+    let mut otx_start_count = 0usize;
+    let mut otx_start_before_own = false;
+    let mut cursor = (0u32, 0u32, 0u32, 0u32);
+    let mut i = 0usize;
+    loop {
+        let witness = match load_witness(i, Source::Input) {
+            Ok(w) => w,
+            Err(SysError::IndexOutOfBound) => break,
+            Err(e) => return Err(e.into()),
+        };
+        check_prealloc(witness.len() as u64)?;
+        if let Ok(r) = ExtendedWitnessReader::from_slice(&witness) {
+            match r.to_enum() {
+                ExtendedWitnessUnionReader::OtxStart(s) => {
+                    otx_start_count += 1;
+                    if otx_start_count > 1 {
+                        return Err(Error::WrongOtxLayout);
+                    }
+                    if i < own_index {
+                        otx_start_before_own = true;
+                        let s = s.to_entity();
+                        cursor = (
+                            unpack_u32(s.start_input_cell()),
+                            unpack_u32(s.start_output_cell()),
+                            unpack_u32(s.start_cell_deps()),
+                            unpack_u32(s.start_header_deps()),
+                        );
+                    }
+                }
+                ExtendedWitnessUnionReader::Otx(o) => {
+                    if i < own_index {
+                        if !otx_start_before_own {
+                            return Err(Error::WrongOtxLayout);
+                        }
+                        let o = o.to_entity();
+                        cursor = (
+                            cursor.0 + unpack_u32(o.input_cells()),
+                            cursor.1 + unpack_u32(o.output_cells()),
+                            cursor.2 + unpack_u32(o.cell_deps()),
+                            cursor.3 + unpack_u32(o.header_deps()),
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+        i += 1;
+    }
+    if otx_start_count == 0 || !otx_start_before_own {
+        return Err(Error::WrongOtxLayout);
+    }
+
+    let message = otx.message().raw_data();
+    check_prealloc(message.len() as u64)?;
+    let lock = otx.lock().raw_data();
+    check_prealloc(lock.len() as u64)?;
+
+    Ok(ResolvedOtx {
+        start_input_cell: cursor.0,
+        start_output_cell: cursor.1,
+        start_cell_deps: cursor.2,
+        start_header_deps: cursor.3,
+        input_cells: unpack_u32(otx.input_cells()),
+        output_cells: unpack_u32(otx.output_cells()),
+        cell_deps: unpack_u32(otx.cell_deps()),
+        header_deps: unpack_u32(otx.header_deps()),
+        message: message.into(),
+        lock: lock.into(),
+    })
+}
+
+//
+// Rule for hashing an Otx, mirroring generate_skeleton_hash/generate_final_hash:
+// seed a fresh hasher with a distinct prefix so an otx hash can never collide
+// with a whole-transaction skeleton/final hash, then absorb the otx message,
+// its four counts as fixed 8-byte LE, and every cell/cell dep/header dep it
+// covers, the same way a segwit witness hashes only its own scoped subset.
+//
+/// Map a range syscall's `SysError::IndexOutOfBound` to `Error::WrongOtxLayout`,
+/// since an out-of-range cell/input/cell-dep/header-dep index here means the
+/// resolved `Otx` offsets don't actually fit the transaction, not a generic
+/// syscall failure. Any other `SysError` passes through unchanged.
+fn map_range_err(e: SysError) -> Error {
+    match e {
+        SysError::IndexOutOfBound => Error::WrongOtxLayout,
+        e => e.into(),
+    }
+}
+
+fn generate_otx_hash(otx: &ResolvedOtx) -> Result<[u8; 32], Error> {
+    let mut hasher = new_blake2b();
+    hasher.update(b"ckb-cobuild-otx-hash");
+    hasher.update(&(otx.message.len() as u64).to_le_bytes());
+    hasher.update(&otx.message);
+    hasher.update(&(otx.input_cells as u64).to_le_bytes());
+    hasher.update(&(otx.output_cells as u64).to_le_bytes());
+    hasher.update(&(otx.cell_deps as u64).to_le_bytes());
+    hasher.update(&(otx.header_deps as u64).to_le_bytes());
+
+    let input_end = otx
+        .start_input_cell
+        .checked_add(otx.input_cells)
+        .ok_or(Error::WrongOtxLayout)?;
+    for i in otx.start_input_cell..input_end {
+        let cell = load_cell(i as usize, Source::Input).map_err(map_range_err)?;
+        hasher.update(cell.as_slice());
+        let data = load_cell_data(i as usize, Source::Input).map_err(map_range_err)?;
+        hasher.update(&(data.len() as u64).to_le_bytes());
+        hasher.update(&data);
+        let input = load_input(i as usize, Source::Input).map_err(map_range_err)?;
+        hasher.update(input.as_slice());
+    }
+
+    let output_end = otx
+        .start_output_cell
+        .checked_add(otx.output_cells)
+        .ok_or(Error::WrongOtxLayout)?;
+    for i in otx.start_output_cell..output_end {
+        let cell = load_cell(i as usize, Source::Output).map_err(map_range_err)?;
+        hasher.update(cell.as_slice());
+        let data = load_cell_data(i as usize, Source::Output).map_err(map_range_err)?;
+        hasher.update(&(data.len() as u64).to_le_bytes());
+        hasher.update(&data);
+    }
+
+    let cell_dep_end = otx
+        .start_cell_deps
+        .checked_add(otx.cell_deps)
+        .ok_or(Error::WrongOtxLayout)?;
+    for i in otx.start_cell_deps..cell_dep_end {
+        let dep = load_cell_dep(i as usize).map_err(map_range_err)?;
+        hasher.update(dep.as_slice());
+    }
+
+    let header_dep_end = otx
+        .start_header_deps
+        .checked_add(otx.header_deps)
+        .ok_or(Error::WrongOtxLayout)?;
+    for i in otx.start_header_deps..header_dep_end {
+        let header = load_header(i as usize, Source::HeaderDep).map_err(map_range_err)?;
+        hasher.update(header.as_slice());
+    }
+
+    let mut output = [0u8; 32];
+    hasher.finalize(&mut output);
+    Ok(output)
+}
+
+///
+/// parse transaction with an open transaction (otx) typed message and
+/// return the same 2 values as parse_typed_message:
+/// 1. digest message, 32 bytes message for signature verification
+/// 2. lock, lock field in this script group's Otx witness
+/// This lets a lock script sign only the portion of the transaction its
+/// Otx covers, instead of the whole skeleton hash.
+///
+pub fn parse_otx_message() -> Result<([u8; 32], Vec<u8>), Error> {
+    let otx = resolve_otx()?;
+    let digest_message = generate_otx_hash(&otx)?;
+    let lock = otx.lock.clone();
+    Ok((digest_message, lock))
 }
@@ -0,0 +1,245 @@
+//! Opt-in verification of a CKB multisig `lock` payload against a digest
+//! produced by `parse_typed_message`/`parse_otx_message`. Modeled on the
+//! `secp256k1_blake160_multisig_all` unlock rules: a 4-byte header, `N`
+//! blake160 pubkey hashes, then `M` recoverable signatures.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use libsecp256k1::{recover, Message, RecoveryId, Signature};
+
+use crate::blake2b::new_blake2b;
+use crate::Error;
+
+/// Size in bytes of a recoverable secp256k1 signature (64-byte signature + 1-byte recovery id).
+pub const SECP_SIGNATURE_SIZE: usize = 65;
+
+const BLAKE160_SIZE: usize = 20;
+const HEADER_SIZE: usize = 4;
+
+///
+/// A parsed multisig `lock` header: how many leading pubkeys must sign
+/// (`require_first_n`), how many signatures are required in total
+/// (`threshold`), and the blake160 hash of every member's pubkey.
+///
+pub struct MultisigConfig {
+    require_first_n: u8,
+    threshold: u8,
+    pubkey_hashes: Vec<[u8; BLAKE160_SIZE]>,
+}
+
+impl MultisigConfig {
+    fn parse(lock: &[u8]) -> Result<(Self, usize), Error> {
+        if lock.len() < HEADER_SIZE {
+            return Err(Error::InvalidMultisig);
+        }
+        let reserved = lock[0];
+        let require_first_n = lock[1];
+        let threshold = lock[2];
+        let pubkey_count = lock[3];
+        if reserved != 0 || threshold == 0 || pubkey_count < threshold || require_first_n > threshold
+        {
+            return Err(Error::InvalidMultisig);
+        }
+
+        let pubkeys_end = HEADER_SIZE + pubkey_count as usize * BLAKE160_SIZE;
+        if lock.len() < pubkeys_end {
+            return Err(Error::InvalidMultisig);
+        }
+        let pubkey_hashes = lock[HEADER_SIZE..pubkeys_end]
+            .chunks_exact(BLAKE160_SIZE)
+            .map(|chunk| chunk.try_into().unwrap())
+            .collect();
+
+        Ok((
+            MultisigConfig {
+                require_first_n,
+                threshold,
+                pubkey_hashes,
+            },
+            pubkeys_end,
+        ))
+    }
+
+    ///
+    /// blake160(header || pubkey hashes), the value a lock script
+    /// compares against its own args to bind this config to a specific cell.
+    ///
+    pub fn hash(&self, header: &[u8; HEADER_SIZE]) -> [u8; BLAKE160_SIZE] {
+        let mut hasher = new_blake2b();
+        hasher.update(header);
+        for pubkey_hash in &self.pubkey_hashes {
+            hasher.update(pubkey_hash);
+        }
+        let mut digest = [0u8; 32];
+        hasher.finalize(&mut digest);
+        let mut blake160 = [0u8; BLAKE160_SIZE];
+        blake160.copy_from_slice(&digest[..BLAKE160_SIZE]);
+        blake160
+    }
+}
+
+fn recover_blake160(digest: &[u8; 32], signature: &[u8]) -> Result<[u8; BLAKE160_SIZE], Error> {
+    if signature.len() != SECP_SIGNATURE_SIZE {
+        return Err(Error::InvalidMultisig);
+    }
+    let recovery_id = RecoveryId::parse(signature[64]).map_err(|_| Error::InvalidMultisig)?;
+    let mut raw_signature = [0u8; 64];
+    raw_signature.copy_from_slice(&signature[..64]);
+    let parsed_signature =
+        Signature::parse_standard(&raw_signature).map_err(|_| Error::InvalidMultisig)?;
+    let message = Message::parse(digest);
+    let pubkey = recover(&message, &parsed_signature, &recovery_id)
+        .map_err(|_| Error::InvalidMultisig)?;
+
+    let mut hasher = new_blake2b();
+    hasher.update(&pubkey.serialize()[1..]);
+    let mut hash = [0u8; 32];
+    hasher.finalize(&mut hash);
+    let mut blake160 = [0u8; BLAKE160_SIZE];
+    blake160.copy_from_slice(&hash[..BLAKE160_SIZE]);
+    Ok(blake160)
+}
+
+///
+/// Verify a CKB multisig `lock` payload against `digest`, the signing
+/// digest produced by `parse_typed_message`/`parse_otx_message`. On success
+/// returns the blake160 hash of the multisig config, for the caller to
+/// compare against its own lock args. Requires at least `threshold`
+/// distinct, valid signatures, with the `require_first_n` leading pubkeys
+/// all among them.
+///
+pub fn verify_multisig(digest: &[u8; 32], lock: &[u8]) -> Result<[u8; BLAKE160_SIZE], Error> {
+    if lock.len() < HEADER_SIZE {
+        return Err(Error::InvalidMultisig);
+    }
+    let header: [u8; HEADER_SIZE] = lock[..HEADER_SIZE].try_into().unwrap();
+    let (config, signatures_start) = MultisigConfig::parse(lock)?;
+    let signatures_end = signatures_start + config.threshold as usize * SECP_SIGNATURE_SIZE;
+    if lock.len() != signatures_end {
+        return Err(Error::InvalidMultisig);
+    }
+
+    let mut used = vec![false; config.pubkey_hashes.len()];
+    let mut valid_count = 0u8;
+    for signature in lock[signatures_start..signatures_end].chunks_exact(SECP_SIGNATURE_SIZE) {
+        let blake160 = recover_blake160(digest, signature)?;
+        let index = config
+            .pubkey_hashes
+            .iter()
+            .position(|hash| hash == &blake160)
+            .ok_or(Error::InvalidMultisig)?;
+        if used[index] {
+            return Err(Error::InvalidMultisig);
+        }
+        used[index] = true;
+        valid_count += 1;
+    }
+
+    if valid_count < config.threshold {
+        return Err(Error::InvalidMultisig);
+    }
+    if used[..config.require_first_n as usize]
+        .iter()
+        .any(|&is_used| !is_used)
+    {
+        return Err(Error::InvalidMultisig);
+    }
+
+    Ok(config.hash(&header))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libsecp256k1::{sign, PublicKey, SecretKey};
+
+    fn blake160_of_pubkey(pubkey: &PublicKey) -> [u8; BLAKE160_SIZE] {
+        let mut hasher = new_blake2b();
+        hasher.update(&pubkey.serialize()[1..]);
+        let mut hash = [0u8; 32];
+        hasher.finalize(&mut hash);
+        let mut blake160 = [0u8; BLAKE160_SIZE];
+        blake160.copy_from_slice(&hash[..BLAKE160_SIZE]);
+        blake160
+    }
+
+    fn sign_recoverable(digest: &[u8; 32], key: &SecretKey) -> [u8; SECP_SIGNATURE_SIZE] {
+        let message = Message::parse(digest);
+        let (signature, recovery_id) = sign(&message, key);
+        let mut out = [0u8; SECP_SIGNATURE_SIZE];
+        out[..64].copy_from_slice(&signature.serialize());
+        out[64] = recovery_id.serialize();
+        out
+    }
+
+    // Builds a lock: header || pubkey hashes of `keys` || signatures from `signers`.
+    fn build_lock(
+        require_first_n: u8,
+        threshold: u8,
+        keys: &[SecretKey],
+        signers: &[&SecretKey],
+        digest: &[u8; 32],
+    ) -> Vec<u8> {
+        let mut lock = vec![0u8, require_first_n, threshold, keys.len() as u8];
+        for key in keys {
+            let pubkey = PublicKey::from_secret_key(key);
+            lock.extend_from_slice(&blake160_of_pubkey(&pubkey));
+        }
+        for signer in signers {
+            lock.extend_from_slice(&sign_recoverable(digest, signer));
+        }
+        lock
+    }
+
+    #[test]
+    fn rejects_require_first_n_beyond_threshold() {
+        let digest = [7u8; 32];
+        let key = SecretKey::parse(&[0x11; 32]).unwrap();
+        // require_first_n (2) > threshold (1): used[..require_first_n] would
+        // panic if this weren't rejected during parsing.
+        let lock = build_lock(2, 1, &[key], &[&key], &digest);
+        assert_eq!(verify_multisig(&digest, &lock), Err(Error::InvalidMultisig));
+    }
+
+    #[test]
+    fn rejects_pubkey_count_below_threshold() {
+        let digest = [7u8; 32];
+        let key = SecretKey::parse(&[0x11; 32]).unwrap();
+        let mut lock = build_lock(1, 1, &[key], &[&key], &digest);
+        // Claim a threshold higher than the single pubkey present.
+        lock[2] = 2;
+        assert_eq!(verify_multisig(&digest, &lock), Err(Error::InvalidMultisig));
+    }
+
+    #[test]
+    fn rejects_duplicate_signature_from_same_signer() {
+        let digest = [7u8; 32];
+        let key_a = SecretKey::parse(&[0x11; 32]).unwrap();
+        let key_b = SecretKey::parse(&[0x22; 32]).unwrap();
+        // threshold 2, but both signatures come from key_a.
+        let lock = build_lock(0, 2, &[key_a, key_b], &[&key_a, &key_a], &digest);
+        assert_eq!(verify_multisig(&digest, &lock), Err(Error::InvalidMultisig));
+    }
+
+    #[test]
+    fn accepts_require_first_n_equal_threshold_equal_pubkey_count() {
+        let digest = [7u8; 32];
+        let key = SecretKey::parse(&[0x11; 32]).unwrap();
+        let lock = build_lock(1, 1, &[key], &[&key], &digest);
+        assert!(verify_multisig(&digest, &lock).is_ok());
+    }
+
+    #[test]
+    fn accepts_valid_m_of_n_round_trip() {
+        let digest = [7u8; 32];
+        let key_a = SecretKey::parse(&[0x11; 32]).unwrap();
+        let key_b = SecretKey::parse(&[0x22; 32]).unwrap();
+        let key_c = SecretKey::parse(&[0x33; 32]).unwrap();
+        // 2-of-3: only key_a and key_c sign.
+        let lock = build_lock(0, 2, &[key_a, key_b, key_c], &[&key_a, &key_c], &digest);
+        let header: [u8; HEADER_SIZE] = lock[..HEADER_SIZE].try_into().unwrap();
+        let (config, _) = MultisigConfig::parse(&lock).unwrap();
+        let expected_hash = config.hash(&header);
+        assert_eq!(verify_multisig(&digest, &lock), Ok(expected_hash));
+    }
+}
@@ -0,0 +1,53 @@
+//! Canonical, CompactSize/BigSize-style variable-length size prefix used by
+//! `generate_skeleton_hash_v2` in place of the fixed 8-byte LE length prefix:
+//! one byte for small values, escalating to 2/4/8 bytes for larger ones.
+//!
+//! This crate has no wire format where a length is itself decoded out of
+//! untrusted witness bytes (every length here comes from an already-framed
+//! molecule field or a materialized `Vec`/slice), so there is no
+//! `read_varsize` counterpart: a canonical-form decoder with no caller would
+//! just be unused, untested "security" code implying a guard that isn't
+//! actually wired to anything. `check_prealloc` covers the lengths this
+//! crate does see.
+
+use blake2b_ref::Blake2b;
+
+use crate::Error;
+
+/// Ceiling on any witness/lock/message length before it is copied into a
+/// further `Vec` or molecule entity. A witness is checked only after
+/// `load_witness` has already loaded it in full, so the allocation of that
+/// first copy is not bounded by this ceiling.
+pub const MAX_WITNESS_PREALLOC: u64 = 1 << 20;
+
+///
+/// Write `len` into `hasher` as a canonical variable-length prefix: the
+/// value itself for `len < 0xfd`, otherwise a `0xfd`/`0xfe`/`0xff` marker
+/// followed by the value as 2/4/8-byte little-endian.
+///
+pub fn write_varsize(hasher: &mut Blake2b, len: u64) {
+    if len < 0xfd {
+        hasher.update(&[len as u8]);
+    } else if len <= 0xffff {
+        hasher.update(&[0xfd]);
+        hasher.update(&(len as u16).to_le_bytes());
+    } else if len <= 0xffff_ffff {
+        hasher.update(&[0xfe]);
+        hasher.update(&(len as u32).to_le_bytes());
+    } else {
+        hasher.update(&[0xff]);
+        hasher.update(&len.to_le_bytes());
+    }
+}
+
+///
+/// Bound an already-known length (a witness or a molecule field's
+/// `raw_data().len()`) against `MAX_WITNESS_PREALLOC`, before it is copied
+/// into a further `Vec` or molecule entity.
+///
+pub fn check_prealloc(len: u64) -> Result<(), Error> {
+    if len > MAX_WITNESS_PREALLOC {
+        return Err(Error::NonCanonicalLength);
+    }
+    Ok(())
+}